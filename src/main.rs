@@ -1,12 +1,23 @@
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
+use std::io::{BufReader, Read};
 use std::path::PathBuf;
-use std::process::exit;
+use std::process::{exit, Command};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use bytesize::ByteSize;
 use colored::*;
-use rayon::prelude::*;
+use futures::StreamExt;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use rand::Rng;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use structopt::StructOpt;
+use tokio::sync::Semaphore;
+use tokio_util::codec::{BytesCodec, FramedRead};
 use walkdir::WalkDir;
 
 #[derive(StructOpt, Debug)]
@@ -21,11 +32,153 @@ struct Opt {
     #[structopt(short, long)]
     tags: String,
 
+    /// Maximum number of attempts for a request before giving up.
+    #[structopt(long, default_value = "5")]
+    max_retries: u32,
+
+    /// Skip uploading files whose checksum the server already has.
+    #[structopt(long)]
+    skip_existing: bool,
+
+    /// Shell out to ffprobe to auto-fill description, duration and upload date.
+    #[structopt(long)]
+    probe: bool,
+
+    /// Tail the given directories and upload new files as they appear, instead of
+    /// exiting after a single pass.
+    #[structopt(long)]
+    watch: bool,
+
+    /// Maximum number of uploads to run at once.
+    #[structopt(long, default_value = "4")]
+    concurrency: usize,
+
+    /// Image tagger HTTP endpoint to auto-tag sampled keyframes against.
+    #[structopt(long)]
+    auto_tag: Option<String>,
+
+    /// Minimum confidence for an auto-tag prediction to be kept.
+    #[structopt(long, default_value = "0.5")]
+    tag_threshold: f32,
+
     /// Files or directories to upload recursively.
     #[structopt(name = "FILE", parse(from_os_str))]
     paths: Vec<PathBuf>,
 }
 
+/// An error from a single HTTP attempt, classified so the retry loop knows
+/// whether trying again is worthwhile.
+#[derive(Debug)]
+enum RequestError {
+    /// A 5xx status, a connection/timeout error, or a 408/429 - worth retrying.
+    Retryable(String),
+    /// Anything else (4xx, malformed responses, etc) - retrying won't help.
+    Fatal(String),
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::Retryable(msg) => write!(f, "{}", msg),
+            RequestError::Fatal(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+impl From<reqwest::Error> for RequestError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() || err.is_connect() {
+            RequestError::Retryable(err.to_string())
+        } else {
+            RequestError::Fatal(err.to_string())
+        }
+    }
+}
+
+fn classify_status(status: StatusCode) -> Option<RequestError> {
+    if status.is_success() {
+        return None;
+    }
+
+    let msg = format!("server responded with {}", status);
+
+    if status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+    {
+        Some(RequestError::Retryable(msg))
+    } else {
+        Some(RequestError::Fatal(msg))
+    }
+}
+
+/// Caps the exponent so a large `--max-retries` can't overflow `2u32.pow`.
+const MAX_BACKOFF_EXPONENT: u32 = 20;
+
+fn backoff_delay(attempt: u32) -> Duration {
+    let base_delay = Duration::from_millis(500);
+    base_delay * 2u32.pow(attempt.min(MAX_BACKOFF_EXPONENT))
+}
+
+/// Calls `operation` at most `max_retries` times in total, trying again while
+/// it returns a `RequestError::Retryable` and sleeping `backoff_delay(attempt)`
+/// plus jitter between tries. Fatal errors are returned immediately.
+async fn retry_with_backoff<T, F, Fut>(
+    max_retries: u32,
+    mut operation: F,
+) -> Result<T, RequestError>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: std::future::Future<Output = Result<T, RequestError>>,
+{
+    let mut attempt = 0;
+
+    loop {
+        match operation(attempt).await {
+            Ok(value) => return Ok(value),
+            Err(RequestError::Retryable(msg)) if attempt + 1 < max_retries => {
+                let jitter = Duration::from_millis(rand::thread_rng().gen_range(0..250));
+
+                eprintln!(
+                    "Retrying after error ({}/{}): {}",
+                    attempt + 1,
+                    max_retries,
+                    msg
+                );
+
+                tokio::time::sleep(backoff_delay(attempt) + jitter).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn compute_checksum(path: &PathBuf) -> Result<String, std::io::Error> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+
+    loop {
+        let count = reader.read(&mut buffer)?;
+
+        if count == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..count]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn should_skip_existing(skip_existing: bool, checksum_exists: bool) -> bool {
+    skip_existing && checksum_exists
+}
+
 fn is_video(path: &PathBuf) -> bool {
     let guess = mime_guess::from_path(path);
 
@@ -35,16 +188,223 @@ fn is_video(path: &PathBuf) -> bool {
     }
 }
 
+#[derive(Deserialize, Debug, Default)]
+struct FfprobeFormat {
+    duration: Option<String>,
+    bit_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct FfprobeStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    width: Option<i64>,
+    height: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct FfprobeOutput {
+    #[serde(default)]
+    format: FfprobeFormat,
+    #[serde(default)]
+    streams: Vec<FfprobeStream>,
+}
+
+struct VideoMetadata {
+    original_upload_date: Option<String>,
+    description: String,
+    // Whether ffprobe actually found a video stream, independent of `mime_guess`.
+    is_video: bool,
+}
+
+fn probe_video(path: &PathBuf) -> Option<VideoMetadata> {
+    let output = Command::new("ffprobe")
+        .args(&[
+            "-v",
+            "quiet",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+        ])
+        .arg(path)
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let probe: FfprobeOutput = serde_json::from_slice(&output.stdout).ok()?;
+
+    let video_stream = probe
+        .streams
+        .iter()
+        .find(|stream| stream.codec_type.as_deref() == Some("video"));
+
+    let resolution = match video_stream.and_then(|stream| Some((stream.width?, stream.height?))) {
+        Some((width, height)) => format!("{}x{}", width, height),
+        None => "unknown resolution".to_owned(),
+    };
+
+    let codec = video_stream
+        .and_then(|stream| stream.codec_name.clone())
+        .unwrap_or_else(|| "unknown codec".to_owned());
+
+    let duration = probe
+        .format
+        .duration
+        .clone()
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    let bit_rate = probe
+        .format
+        .bit_rate
+        .clone()
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    let description = format!("{}, {}, {}s, {} bps", resolution, codec, duration, bit_rate);
+
+    let original_upload_date = probe.format.tags.get("creation_time").cloned();
+
+    Some(VideoMetadata {
+        original_upload_date,
+        description,
+        is_video: video_stream.is_some(),
+    })
+}
+
+fn is_candidate_video(path: &PathBuf, probe: bool) -> bool {
+    is_video(path)
+        || (probe
+            && probe_video(path)
+                .map(|metadata| metadata.is_video)
+                .unwrap_or(false))
+}
+
+const KEYFRAME_INTERVAL_SECS: u32 = 10;
+const MAX_KEYFRAMES: u32 = 5;
+
+fn extract_keyframes(path: &PathBuf) -> Option<PathBuf> {
+    let dir_name = format!(
+        "mx-keyframes-{}-{:x}",
+        path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("video"),
+        rand::thread_rng().gen::<u32>()
+    );
+    let frame_dir = std::env::temp_dir().join(dir_name);
+    std::fs::create_dir_all(&frame_dir).ok()?;
+
+    let status = Command::new("ffmpeg")
+        .arg("-y")
+        .arg("-i")
+        .arg(path)
+        .args(&[
+            "-vf",
+            &format!("fps=1/{}", KEYFRAME_INTERVAL_SECS),
+            "-frames:v",
+            &MAX_KEYFRAMES.to_string(),
+        ])
+        .arg(frame_dir.join("frame-%03d.jpg"))
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .ok()?;
+
+    if !status.success() {
+        let _ = std::fs::remove_dir_all(&frame_dir);
+        return None;
+    }
+
+    Some(frame_dir)
+}
+
+type TagPredictions = HashMap<String, f32>;
+
+async fn tag_frame(endpoint: &str, frame_path: &PathBuf, threshold: f32) -> HashSet<String> {
+    let bytes = match tokio::fs::read(frame_path).await {
+        Ok(bytes) => bytes,
+        Err(_) => return HashSet::new(),
+    };
+
+    let part = match reqwest::multipart::Part::bytes(bytes)
+        .file_name("frame.jpg")
+        .mime_str("image/jpeg")
+    {
+        Ok(part) => part,
+        Err(_) => return HashSet::new(),
+    };
+
+    let form = reqwest::multipart::Form::new().part("image", part);
+
+    let response = match reqwest::Client::new()
+        .post(endpoint)
+        .multipart(form)
+        .send()
+        .await
+    {
+        Ok(response) => response,
+        Err(_) => return HashSet::new(),
+    };
+
+    let predictions: TagPredictions = match response.json().await {
+        Ok(predictions) => predictions,
+        Err(_) => return HashSet::new(),
+    };
+
+    predictions
+        .into_iter()
+        .filter(|(_, confidence)| *confidence >= threshold)
+        .map(|(tag, _)| tag)
+        .collect()
+}
+
+async fn auto_tag_video(endpoint: &str, path: &PathBuf, threshold: f32) -> HashSet<String> {
+    let probe_path = path.clone();
+    let frame_dir = match tokio::task::spawn_blocking(move || extract_keyframes(&probe_path))
+        .await
+        .unwrap()
+    {
+        Some(dir) => dir,
+        None => return HashSet::new(),
+    };
+
+    let frames: Vec<PathBuf> = std::fs::read_dir(&frame_dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut tags = HashSet::new();
+
+    for frame in &frames {
+        tags.extend(tag_frame(endpoint, frame, threshold).await);
+    }
+
+    let _ = std::fs::remove_dir_all(&frame_dir);
+
+    tags
+}
+
 #[derive(Serialize, Debug)]
 pub struct NewUploadRequest<'a> {
     file_name: &'a str,
     content_length: i64,
+    checksum: &'a str,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct NewUploadResponse {
     id: String,
     url: String,
+    #[serde(default)]
+    checksum_exists: bool,
 }
 
 #[derive(Deserialize, Debug)]
@@ -53,65 +413,116 @@ pub struct NewUploadError {
     reason: String,
 }
 
-fn begin_upload(
+async fn begin_upload(
     host: &str,
     api_token: &str,
     path: &PathBuf,
+    checksum: &str,
+    max_retries: u32,
 ) -> Result<NewUploadResponse, Box<dyn std::error::Error>> {
     let metadata = std::fs::metadata(&path).unwrap();
     let file_name = path.file_name().unwrap().to_str().unwrap();
     let file_size = metadata.len() as i64;
 
-    println!(
-        "Uploading \"{}\" ({})",
-        file_name,
-        ByteSize(file_size as u64)
-    );
+    let endpoint = format!("{}/api/v1/uploads", host);
 
-    let new_upload_request = NewUploadRequest {
-        file_name,
-        content_length: file_size,
-    };
+    let result = retry_with_backoff(max_retries, |_attempt| async {
+        let new_upload_request = NewUploadRequest {
+            file_name,
+            content_length: file_size,
+            checksum,
+        };
 
-    let endpoint = format!("{}/api/v1/uploads", host);
+        let response = reqwest::Client::new()
+            .post(&endpoint)
+            .header("content-type", "application/json")
+            .header("authorization", format!("Bearer {}", api_token))
+            .json(&new_upload_request)
+            .send()
+            .await?;
 
-    let response = reqwest::blocking::Client::new()
-        .post(&endpoint)
-        .header("content-type", "application/json")
-        .header("authorization", format!("Bearer {}", api_token))
-        .json(&new_upload_request)
-        .send()?;
+        if response.status() == StatusCode::FORBIDDEN {
+            return Err(RequestError::Fatal("Invalid API key".to_owned()));
+        }
 
-    if response.status() == 403 {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Invalid API key",
-        )));
-    }
+        if response.status() == StatusCode::BAD_REQUEST {
+            let json: NewUploadError = response
+                .json()
+                .await
+                .map_err(|err| RequestError::Fatal(err.to_string()))?;
 
-    if response.status() == 400 {
-        let json: NewUploadError = response.json()?;
+            return Err(RequestError::Fatal(json.reason));
+        }
 
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            json.reason,
-        )));
-    }
+        if let Some(err) = classify_status(response.status()) {
+            return Err(err);
+        }
 
-    let json = response.json()?;
+        response
+            .json()
+            .await
+            .map_err(|err| RequestError::Fatal(err.to_string()))
+    })
+    .await;
 
-    Ok(json)
+    result.map_err(|err| err.into())
 }
 
-fn upload_file(path: &PathBuf, url: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let file = File::open(path).unwrap();
+async fn upload_file(
+    path: &PathBuf,
+    url: &str,
+    progress_bar: &ProgressBar,
+    total_bar: &ProgressBar,
+    max_retries: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let result = retry_with_backoff(max_retries, |_attempt| async {
+        progress_bar.set_position(0);
+
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|err| RequestError::Fatal(err.to_string()))?;
 
-    reqwest::blocking::Client::new()
-        .put(url)
-        .body(file)
-        .send()?;
+        let progress_bar = progress_bar.clone();
+        let total_bar = total_bar.clone();
+        // Bytes this attempt has added to `total_bar`, so a failed attempt can
+        // back them out instead of leaving a retried upload double-counted.
+        let attempt_bytes = Arc::new(AtomicU64::new(0));
+        let stream_attempt_bytes = attempt_bytes.clone();
+
+        let stream = FramedRead::new(file, BytesCodec::new()).map(move |chunk| {
+            if let Ok(bytes) = &chunk {
+                progress_bar.inc(bytes.len() as u64);
+                total_bar.inc(bytes.len() as u64);
+                stream_attempt_bytes.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+            }
+
+            chunk.map(|bytes| bytes.freeze())
+        });
+
+        let response = reqwest::Client::new()
+            .put(url)
+            .body(reqwest::Body::wrap_stream(stream))
+            .send()
+            .await;
+
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                total_bar.dec(attempt_bytes.load(Ordering::Relaxed));
+                return Err(err.into());
+            }
+        };
+
+        if let Some(err) = classify_status(response.status()) {
+            total_bar.dec(attempt_bytes.load(Ordering::Relaxed));
+            return Err(err);
+        }
 
-    Ok(())
+        Ok(())
+    })
+    .await;
+
+    result.map_err(|err| err.into())
 }
 
 #[derive(Serialize, Debug)]
@@ -121,6 +532,7 @@ pub struct FinalizeUploadRequest {
     source: String,
     description: String,
     original_upload_date: Option<String>,
+    checksum: String,
 }
 
 #[derive(Deserialize, Debug)]
@@ -129,38 +541,327 @@ pub struct FinalizeUploadResponse {
     url: String,
 }
 
-fn finalize_file(
+async fn finalize_file(
     finalize_request: &FinalizeUploadRequest,
     host: &str,
     api_token: &str,
+    max_retries: u32,
 ) -> Result<NewUploadResponse, Box<dyn std::error::Error>> {
     let endpoint = format!("{}/api/v1/uploads/finalize", host);
 
-    let response = reqwest::blocking::Client::new()
-        .post(&endpoint)
-        .header("content-type", "application/json")
-        .header("authorization", format!("Bearer {}", api_token))
-        .json(&finalize_request)
-        .send()?;
+    let result = retry_with_backoff(max_retries, |_attempt| async {
+        let response = reqwest::Client::new()
+            .post(&endpoint)
+            .header("content-type", "application/json")
+            .header("authorization", format!("Bearer {}", api_token))
+            .json(&finalize_request)
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::FORBIDDEN {
+            return Err(RequestError::Fatal("Invalid API key".to_owned()));
+        }
+
+        if let Some(err) = classify_status(response.status()) {
+            return Err(err);
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|err| RequestError::Fatal(err.to_string()))
+    })
+    .await;
+
+    result.map_err(|err| err.into())
+}
 
-    if response.status() == 403 {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Invalid API key",
-        )));
+fn progress_style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .unwrap()
+        .progress_chars("#>-")
+}
+
+async fn upload_one(
+    file_path: &PathBuf,
+    host: &str,
+    api_key: &str,
+    tags: &str,
+    max_retries: u32,
+    skip_existing: bool,
+    probe: bool,
+    auto_tag: Option<&str>,
+    tag_threshold: f32,
+    multi_progress: &MultiProgress,
+    total_bar: &ProgressBar,
+) {
+    let hash_path = file_path.clone();
+    let checksum = match tokio::task::spawn_blocking(move || compute_checksum(&hash_path))
+        .await
+        .unwrap()
+    {
+        Ok(checksum) => checksum,
+        Err(err) => {
+            eprintln!(
+                "[{}] Error hashing file: {}",
+                file_path.to_str().unwrap(),
+                err
+            );
+            return;
+        }
+    };
+
+    match begin_upload(host, api_key, file_path, &checksum, max_retries).await {
+        Ok(response) if should_skip_existing(skip_existing, response.checksum_exists) => {
+            println!("[{}] [skipped]", file_path.to_str().unwrap());
+
+            let content_length = std::fs::metadata(file_path).map(|m| m.len()).unwrap_or(0);
+            total_bar.dec_length(content_length);
+        }
+        Ok(response) => {
+            let metadata = if probe {
+                let probe_path = file_path.clone();
+                tokio::task::spawn_blocking(move || probe_video(&probe_path))
+                    .await
+                    .unwrap()
+            } else {
+                None
+            };
+
+            let content_length = std::fs::metadata(file_path).map(|m| m.len()).unwrap_or(0);
+            let file_name = file_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("file")
+                .to_owned();
+
+            let progress_bar = multi_progress.add(ProgressBar::new(content_length));
+            progress_bar.set_style(progress_style(
+                "{msg} [{bar:30.green/white}] {bytes}/{total_bytes}",
+            ));
+            progress_bar.set_message(format!("{} ({})", file_name, ByteSize(content_length)));
+
+            let upload_result = upload_file(
+                file_path,
+                &response.url,
+                &progress_bar,
+                total_bar,
+                max_retries,
+            )
+            .await;
+
+            progress_bar.finish_and_clear();
+
+            match upload_result {
+                Ok(_) => {
+                    let final_tags = match auto_tag {
+                        Some(endpoint) => {
+                            let mut tag_set: HashSet<String> = tags
+                                .split(',')
+                                .map(|tag| tag.trim().to_owned())
+                                .filter(|tag| !tag.is_empty())
+                                .collect();
+
+                            tag_set
+                                .extend(auto_tag_video(endpoint, file_path, tag_threshold).await);
+
+                            tag_set.into_iter().collect::<Vec<_>>().join(",")
+                        }
+                        None => tags.to_owned(),
+                    };
+
+                    let request = FinalizeUploadRequest {
+                        id: response.id,
+                        tags: final_tags,
+                        source: "".to_owned(),
+                        description: metadata
+                            .as_ref()
+                            .map(|metadata| metadata.description.clone())
+                            .unwrap_or_default(),
+                        original_upload_date: metadata
+                            .as_ref()
+                            .and_then(|metadata| metadata.original_upload_date.clone()),
+                        checksum: checksum.clone(),
+                    };
+
+                    match finalize_file(&request, host, api_key, max_retries).await {
+                        Ok(response) => {
+                            println!(
+                                "[{}] Uploaded: {}",
+                                file_path.to_str().unwrap(),
+                                response.url
+                            );
+                        }
+                        Err(err) => {
+                            eprintln!("[{}] Error: {}", file_path.to_str().unwrap(), err);
+                        }
+                    }
+                }
+                Err(err) => {
+                    eprintln!("[{}] Error: {}", file_path.to_str().unwrap(), err);
+                }
+            }
+        }
+        Err(err) => {
+            eprintln!("[{}] Error: {}", file_path.to_str().unwrap(), err);
+        }
+    }
+}
+
+async fn upload_batch(
+    files: Vec<PathBuf>,
+    host: &str,
+    api_key: &str,
+    tags: &str,
+    max_retries: u32,
+    skip_existing: bool,
+    probe: bool,
+    auto_tag: Option<&str>,
+    tag_threshold: f32,
+    concurrency: usize,
+) {
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let multi_progress = Arc::new(MultiProgress::new());
+
+    let total_bytes: u64 = files
+        .iter()
+        .filter_map(|file| std::fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+
+    let total_bar = multi_progress.add(ProgressBar::new(total_bytes));
+    total_bar.set_style(progress_style(
+        "{msg} [{bar:40.cyan/blue}] {bytes}/{total_bytes}",
+    ));
+    total_bar.set_message("Total".to_owned());
+
+    let mut handles = Vec::with_capacity(files.len());
+
+    for file_path in files {
+        let semaphore = semaphore.clone();
+        let multi_progress = multi_progress.clone();
+        let total_bar = total_bar.clone();
+        let host = host.to_owned();
+        let api_key = api_key.to_owned();
+        let tags = tags.to_owned();
+        let auto_tag = auto_tag.map(|endpoint| endpoint.to_owned());
+
+        handles.push(tokio::spawn(async move {
+            let _permit = semaphore.acquire_owned().await.unwrap();
+
+            upload_one(
+                &file_path,
+                &host,
+                &api_key,
+                &tags,
+                max_retries,
+                skip_existing,
+                probe,
+                auto_tag.as_deref(),
+                tag_threshold,
+                &multi_progress,
+                &total_bar,
+            )
+            .await;
+        }));
     }
 
-    let json = response.json()?;
+    for handle in handles {
+        let _ = handle.await;
+    }
 
-    Ok(json)
+    total_bar.finish_and_clear();
 }
 
-fn main() {
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(4)
-        .build_global()
-        .unwrap();
+/// Polls `directories` for new video files and uploads each one exactly
+/// once, the way a build-event uploader tails a drop folder. A file is only
+/// enqueued once its size has been stable across two consecutive polls, so
+/// we don't grab something still being written. Runs until interrupted.
+async fn watch_directories(
+    directories: &[PathBuf],
+    host: &str,
+    api_key: &str,
+    tags: &str,
+    max_retries: u32,
+    skip_existing: bool,
+    probe: bool,
+    auto_tag: Option<&str>,
+    tag_threshold: f32,
+    concurrency: usize,
+) {
+    let poll_interval = Duration::from_secs(2);
+    let mut processed: HashSet<PathBuf> = HashSet::new();
+    let mut last_seen_sizes: HashMap<PathBuf, u64> = HashMap::new();
+
+    println!(
+        "Watching {} director{} for new files (Ctrl+C to stop)...",
+        directories.len(),
+        if directories.len() == 1 { "y" } else { "ies" }
+    );
+
+    loop {
+        let mut stable_files: Vec<PathBuf> = Vec::new();
+
+        for dir in directories {
+            for entry in WalkDir::new(dir) {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(_) => continue,
+                };
+
+                let path = entry.path().to_owned();
+
+                if processed.contains(&path) || !path.is_file() {
+                    continue;
+                }
+
+                let size = match std::fs::metadata(&path) {
+                    Ok(metadata) => metadata.len(),
+                    Err(_) => continue,
+                };
+
+                match last_seen_sizes.get(&path) {
+                    Some(&previous_size) if previous_size == size => {
+                        last_seen_sizes.remove(&path);
+
+                        // Only probe once the file has stopped growing, so a
+                        // multi-GB file being written doesn't get re-probed
+                        // on every poll while it's still unstable.
+                        if is_candidate_video(&path, probe) {
+                            stable_files.push(path.clone());
+                        }
+
+                        processed.insert(path);
+                    }
+                    _ => {
+                        last_seen_sizes.insert(path, size);
+                    }
+                }
+            }
+        }
 
+        if !stable_files.is_empty() {
+            upload_batch(
+                stable_files,
+                host,
+                api_key,
+                tags,
+                max_retries,
+                skip_existing,
+                probe,
+                auto_tag,
+                tag_threshold,
+                concurrency,
+            )
+            .await;
+        }
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+#[tokio::main]
+async fn main() {
     let opt = Opt::from_args();
     let mut files: Vec<PathBuf> = Vec::new();
     let mut directories: Vec<PathBuf> = Vec::new();
@@ -168,6 +869,13 @@ fn main() {
     let host = opt.host.clone();
     let api_key = opt.api_key.clone();
     let tags = opt.tags.clone();
+    let max_retries = opt.max_retries;
+    let skip_existing = opt.skip_existing;
+    let probe = opt.probe;
+    let watch = opt.watch;
+    let concurrency = opt.concurrency;
+    let auto_tag = opt.auto_tag.clone();
+    let tag_threshold = opt.tag_threshold;
 
     for path in opt.paths.into_iter() {
         if path.is_dir() {
@@ -180,13 +888,57 @@ fn main() {
         }
     }
 
+    if watch {
+        if directories.is_empty() && files.is_empty() {
+            eprintln!("--watch requires at least one file or directory.");
+            exit(1);
+        }
+
+        if !files.is_empty() {
+            upload_batch(
+                files,
+                &host,
+                &api_key,
+                &tags,
+                max_retries,
+                skip_existing,
+                probe,
+                auto_tag.as_deref(),
+                tag_threshold,
+                concurrency,
+            )
+            .await;
+        }
+
+        if !directories.is_empty() {
+            watch_directories(
+                &directories,
+                &host,
+                &api_key,
+                &tags,
+                max_retries,
+                skip_existing,
+                probe,
+                auto_tag.as_deref(),
+                tag_threshold,
+                concurrency,
+            )
+            .await;
+        }
+
+        return;
+    }
+
     for dir in directories.iter() {
         for entry in WalkDir::new(dir) {
             files.push(entry.unwrap().path().to_owned());
         }
     }
 
-    files = files.into_iter().filter(|file| is_video(file)).collect();
+    files = files
+        .into_iter()
+        .filter(|file| is_candidate_video(file, probe))
+        .collect();
 
     if files.len() == 0 {
         eprintln!("No video files found.");
@@ -199,33 +951,105 @@ fn main() {
         &opt.tags.bold()
     );
 
-    files.into_par_iter().for_each(|file_path| {
-        match begin_upload(&host, &api_key, &file_path)
-            .and_then(|response| match upload_file(&file_path, &response.url) {
-                Ok(_) => Ok(response),
-                Err(err) => Err(err),
-            })
-            .and_then(|response| {
-                let request = FinalizeUploadRequest {
-                    id: response.id,
-                    tags: tags.clone(),
-                    source: "".to_owned(),
-                    description: "".to_owned(),
-                    original_upload_date: None,
-                };
+    upload_batch(
+        files,
+        &host,
+        &api_key,
+        &tags,
+        max_retries,
+        skip_existing,
+        probe,
+        auto_tag.as_deref(),
+        tag_threshold,
+        concurrency,
+    )
+    .await;
+}
 
-                finalize_file(&request, &host, &api_key)
-            }) {
-            Ok(response) => {
-                println!(
-                    "[{}] Uploaded: {}",
-                    &file_path.to_str().unwrap(),
-                    response.url
-                );
-            }
-            Err(err) => {
-                eprintln!("[{}] Error: {}", &file_path.to_str().unwrap(), err);
-            }
-        }
-    })
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_status_treats_server_errors_and_throttling_as_retryable() {
+        assert!(matches!(
+            classify_status(StatusCode::INTERNAL_SERVER_ERROR),
+            Some(RequestError::Retryable(_))
+        ));
+        assert!(matches!(
+            classify_status(StatusCode::TOO_MANY_REQUESTS),
+            Some(RequestError::Retryable(_))
+        ));
+        assert!(matches!(
+            classify_status(StatusCode::REQUEST_TIMEOUT),
+            Some(RequestError::Retryable(_))
+        ));
+    }
+
+    #[test]
+    fn classify_status_treats_other_client_errors_as_fatal() {
+        assert!(matches!(
+            classify_status(StatusCode::NOT_FOUND),
+            Some(RequestError::Fatal(_))
+        ));
+        assert!(matches!(
+            classify_status(StatusCode::BAD_REQUEST),
+            Some(RequestError::Fatal(_))
+        ));
+    }
+
+    #[test]
+    fn classify_status_is_none_for_success() {
+        assert!(classify_status(StatusCode::OK).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_with_backoff_makes_at_most_max_retries_attempts() {
+        let attempts = AtomicU64::new(0);
+        let max_retries = 3;
+
+        let result: Result<(), RequestError> = retry_with_backoff(max_retries, |_attempt| {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            async { Err(RequestError::Retryable("boom".to_owned())) }
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::Relaxed), max_retries as u64);
+    }
+
+    #[test]
+    fn backoff_delay_doubles_each_attempt() {
+        assert_eq!(backoff_delay(0), Duration::from_millis(500));
+        assert_eq!(backoff_delay(1), Duration::from_millis(1000));
+        assert_eq!(backoff_delay(2), Duration::from_millis(2000));
+    }
+
+    #[test]
+    fn backoff_delay_caps_instead_of_overflowing() {
+        assert_eq!(backoff_delay(MAX_BACKOFF_EXPONENT), backoff_delay(40));
+    }
+
+    #[test]
+    fn compute_checksum_matches_known_sha256() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("mx-checksum-test-{}", std::process::id()));
+        std::fs::write(&path, b"hello world").unwrap();
+
+        let checksum = compute_checksum(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            checksum,
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde"
+        );
+    }
+
+    #[test]
+    fn should_skip_existing_requires_both_flags() {
+        assert!(should_skip_existing(true, true));
+        assert!(!should_skip_existing(true, false));
+        assert!(!should_skip_existing(false, true));
+        assert!(!should_skip_existing(false, false));
+    }
 }